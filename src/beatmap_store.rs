@@ -1,83 +1,118 @@
-use std::{
-    io::{Cursor, Write},
-    path::PathBuf,
-};
+use std::{io::Write, path::PathBuf, sync::Arc, time::Duration};
 
 use anyhow::Context;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{Stream, TryStreamExt};
+use rusty_s3::{Bucket, Credentials, S3Action, UrlStyle};
+use tokio::{io::AsyncRead, sync::mpsc};
+use tokio_stream::wrappers::ReceiverStream;
 use tracing::info;
-use tracing::warn;
 
-use crate::{BeatmapUrlProvider, HTTP_CLIENT};
+use crate::{
+    mirrors::{BeatmapUrlProvider, MirrorConfig, MirrorHealthTracker},
+    HTTP_CLIENT,
+};
 
 pub static MAP_DIRECTORY: &str = "osu_maps";
 
-fn map_directory() -> PathBuf {
-    let mut dir = PathBuf::from(".");
-    dir.push(MAP_DIRECTORY);
-    dir
+/// How long a signed request to the object store is valid for.
+const PRESIGN_DURATION: Duration = Duration::from_secs(60);
+
+/// How many chunk bytes of a `.osz` file are held in memory at once while
+/// streaming it into the response zip.
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How the response zip compresses each entry. `Stored` is the default,
+/// matching current behaviour; `Deflated` trades CPU for a smaller response.
+#[derive(Clone, Copy)]
+pub enum ZipCompression {
+    Stored,
+    Deflated { level: i32 },
 }
 
-pub fn find_non_downloaded_maps(beatmap_ids: &[u64]) -> anyhow::Result<Vec<u64>> {
-    let downloaded_maps_iter =
-        std::fs::read_dir(map_directory()).context("Error while reading map directory.")?;
-    let downloaded_map_ids: Vec<_> = downloaded_maps_iter
-        .filter_map(|dir_entry_res| dir_entry_res.ok())
-        .filter_map(|dir_entry| dir_entry.file_name().into_string().ok())
-        .collect();
-    warn!("Downloaded maps {:?}", downloaded_map_ids);
-
-    let mut absent_maps = Vec::new();
-
-    for beatmap_id in beatmap_ids {
-        let beatmap_file_name = beatmap_id.to_string() + ".osz";
-        if !downloaded_map_ids.contains(&beatmap_file_name) {
-            info!(beatmap_id, "Map `{beatmap_id}` is not downloaded.");
-            absent_maps.push(*beatmap_id);
+impl ZipCompression {
+    fn into_options(self) -> zip::write::FileOptions {
+        match self {
+            ZipCompression::Stored => zip::write::FileOptions::default()
+                .compression_method(zip::CompressionMethod::Stored),
+            ZipCompression::Deflated { level } => zip::write::FileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated)
+                .compression_level(Some(level)),
         }
     }
-
-    info!("Total {} maps not found.", absent_maps.len());
-    Ok(absent_maps)
 }
 
-pub async fn download_map(beatmap_id: u64) -> anyhow::Result<()> {
-    let mut url_provider = BeatmapUrlProvider::new(beatmap_id);
+/// Abstracts over where downloaded `.osz` beatmap archives are cached, so the
+/// service can run against local disk or a shared remote object store without
+/// the rest of the code caring which one is active.
+#[async_trait]
+pub trait BeatmapStore: Send + Sync {
+    /// Returns whether `beatmap_id` is already present in the store.
+    async fn contains(&self, beatmap_id: u64) -> anyhow::Result<bool>;
 
-    loop {
-        let response = HTTP_CLIENT.get(url_provider.get_next_url()?).send().await;
-        info!("Downloading map {}", beatmap_id);
+    /// Writes the full `.osz` archive bytes for `beatmap_id`.
+    async fn put(&self, beatmap_id: u64, bytes: Bytes) -> anyhow::Result<()>;
 
-        if response.is_err() {
-            info!(
-                "Error returned while downloading map `{}`. Trying the next mirror.",
-                beatmap_id
-            );
-            continue;
-        }
-        let response = response.unwrap();
+    /// Opens the stored `.osz` archive for `beatmap_id` for reading.
+    async fn get(&self, beatmap_id: u64) -> anyhow::Result<Box<dyn AsyncRead + Send + Unpin>>;
 
-        if !response.status().is_success() {
-            info!(
-                "Mirror returned {}. Trying the next mirror.",
-                response.status()
-            );
-            continue;
+    /// Filters `beatmap_ids` down to the ones not yet present in the store.
+    async fn find_non_downloaded(&self, beatmap_ids: &[u64]) -> anyhow::Result<Vec<u64>> {
+        let mut absent_maps = Vec::new();
+
+        for beatmap_id in beatmap_ids {
+            if !self.contains(*beatmap_id).await? {
+                info!(beatmap_id, "Map `{beatmap_id}` is not downloaded.");
+                absent_maps.push(*beatmap_id);
+            }
         }
 
-        let mut file_path = map_directory();
+        info!("Total {} maps not found.", absent_maps.len());
+        Ok(absent_maps)
+    }
+}
+
+/// Caches beatmap archives on the local filesystem, under [`MAP_DIRECTORY`].
+pub struct LocalFsBeatmapStore {
+    directory: PathBuf,
+}
+
+impl LocalFsBeatmapStore {
+    pub fn new() -> Self {
+        let mut directory = PathBuf::from(".");
+        directory.push(MAP_DIRECTORY);
+        Self { directory }
+    }
+
+    fn file_path(&self, beatmap_id: u64) -> PathBuf {
+        let mut file_path = self.directory.clone();
         file_path.push(beatmap_id.to_string());
         file_path.set_extension("osz");
-        let mut content = Cursor::new(
-            response
-                .bytes()
-                .await
-                .context("Unable to convert body to bytes.")?,
-        );
+        file_path
+    }
+}
+
+impl Default for LocalFsBeatmapStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl BeatmapStore for LocalFsBeatmapStore {
+    async fn contains(&self, beatmap_id: u64) -> anyhow::Result<bool> {
+        Ok(tokio::fs::try_exists(self.file_path(beatmap_id))
+            .await
+            .context("Unable to check if map file exists.")?)
+    }
+
+    async fn put(&self, beatmap_id: u64, bytes: Bytes) -> anyhow::Result<()> {
+        let file_path = self.file_path(beatmap_id);
 
         // We check if another downloader task (spawned from another request) already created the file.
         // If so do not write to it.
-        // TODO!: Move file writes to a worker task.
-        if file_path.exists() {
+        if self.contains(beatmap_id).await? {
             info!(
                 "Map `{}` already exists in the file system. Download yielded to other task.",
                 beatmap_id
@@ -85,52 +120,284 @@ pub async fn download_map(beatmap_id: u64) -> anyhow::Result<()> {
             return Ok(());
         }
 
-        tokio::task::spawn_blocking(move || {
+        tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
             let mut file = std::fs::OpenOptions::new()
                 .write(true)
                 .create_new(true)
                 .open(file_path.clone())
                 .context("Unable to create map file.")?;
 
-            let copy_res =
-                std::io::copy(&mut content, &mut file).context("Unable to write map data to file.");
-
-            if copy_res.is_err() {
+            if let Err(err) = std::io::copy(&mut bytes.as_ref(), &mut file)
+                .context("Unable to write map data to file.")
+            {
                 info!(
-                    "Unable to copy beatmap `{}` to file. Deleting artifact.",
+                    "Unable to write beatmap `{}` to file. Deleting artifact.",
                     beatmap_id
                 );
                 std::fs::remove_file(file_path).context("Unable to delete the empty file.")?;
+                return Err(err);
             }
 
-            copy_res
+            Ok(())
         })
         .await??;
 
+        Ok(())
+    }
+
+    async fn get(&self, beatmap_id: u64) -> anyhow::Result<Box<dyn AsyncRead + Send + Unpin>> {
+        let file = tokio::fs::File::open(self.file_path(beatmap_id))
+            .await
+            .context("Unable to open cached map file.")?;
+        Ok(Box::new(file))
+    }
+}
+
+/// Caches beatmap archives in an S3/GCS-compatible object store, under the
+/// `beatmaps/{id}.osz` key convention. Every request is SigV4-signed with
+/// [`Credentials`] and sent through [`HTTP_CLIENT`] so uploads and downloads
+/// get the same retry behaviour as the rest of the service.
+pub struct RemoteBeatmapStore {
+    bucket: Bucket,
+    credentials: Credentials,
+}
+
+impl RemoteBeatmapStore {
+    pub fn new(
+        endpoint: String,
+        bucket: String,
+        region: String,
+        access_key: String,
+        secret_key: String,
+    ) -> anyhow::Result<Self> {
+        let endpoint = endpoint
+            .parse()
+            .context("Invalid object store endpoint URL.")?;
+        let bucket = Bucket::new(endpoint, UrlStyle::Path, bucket, region)
+            .context("Invalid object store bucket configuration.")?;
+        let credentials = Credentials::new(access_key, secret_key);
+
+        Ok(Self { bucket, credentials })
+    }
+
+    fn object_key(beatmap_id: u64) -> String {
+        format!("beatmaps/{beatmap_id}.osz")
+    }
+}
+
+#[async_trait]
+impl BeatmapStore for RemoteBeatmapStore {
+    async fn contains(&self, beatmap_id: u64) -> anyhow::Result<bool> {
+        let url = self
+            .bucket
+            .head_object(Some(&self.credentials), &Self::object_key(beatmap_id))
+            .sign(PRESIGN_DURATION);
+
+        let response = HTTP_CLIENT
+            .head(url)
+            .send()
+            .await
+            .context("Unable to HEAD beatmap object.")?;
+
+        Ok(response.status().is_success())
+    }
+
+    async fn put(&self, beatmap_id: u64, bytes: Bytes) -> anyhow::Result<()> {
+        let url = self
+            .bucket
+            .put_object(Some(&self.credentials), &Self::object_key(beatmap_id))
+            .sign(PRESIGN_DURATION);
+
+        let response = HTTP_CLIENT
+            .put(url)
+            .body(bytes)
+            .send()
+            .await
+            .context("Unable to PUT beatmap object.")?;
+
+        if !response.status().is_success() {
+            anyhow::bail!(
+                "Object store rejected upload of beatmap `{beatmap_id}` with status {}",
+                response.status()
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn get(&self, beatmap_id: u64) -> anyhow::Result<Box<dyn AsyncRead + Send + Unpin>> {
+        let url = self
+            .bucket
+            .get_object(Some(&self.credentials), &Self::object_key(beatmap_id))
+            .sign(PRESIGN_DURATION);
+
+        let response = HTTP_CLIENT
+            .get(url)
+            .send()
+            .await
+            .context("Unable to GET beatmap object.")?;
+
+        if !response.status().is_success() {
+            // Transient or missing object: the caller (`stream_zip_beatmaps`) falls
+            // back to re-downloading the beatmap from the osu mirrors on this error
+            // instead of serving a broken archive.
+            anyhow::bail!(
+                "Beatmap `{beatmap_id}` is missing or corrupt in the object store (status {}).",
+                response.status()
+            );
+        }
+
+        let stream = response
+            .bytes_stream()
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err));
+        Ok(Box::new(tokio_util::io::StreamReader::new(stream)))
+    }
+}
+
+pub async fn download_map(
+    beatmap_id: u64,
+    store: &dyn BeatmapStore,
+    mirror_config: &MirrorConfig,
+    mirror_health: &MirrorHealthTracker,
+) -> anyhow::Result<()> {
+    let mut url_provider = BeatmapUrlProvider::new(beatmap_id, mirror_config, mirror_health);
+
+    loop {
+        let url = url_provider.get_next_url()?;
+        let mirror_name = url_provider
+            .current_mirror_name()
+            .expect("a URL was just produced for the current mirror")
+            .to_string();
+
+        let response = HTTP_CLIENT.get(url).send().await;
+        info!("Downloading map {}", beatmap_id);
+
+        let response = match response {
+            Ok(response) if response.status().is_success() => response,
+            Ok(response) => {
+                info!(
+                    "Mirror `{}` returned {}. Trying the next mirror.",
+                    mirror_name,
+                    response.status()
+                );
+                mirror_health.record_failure(&mirror_name);
+                continue;
+            }
+            Err(_) => {
+                info!(
+                    "Error returned while downloading map `{}` from mirror `{}`. Trying the next mirror.",
+                    beatmap_id, mirror_name
+                );
+                mirror_health.record_failure(&mirror_name);
+                continue;
+            }
+        };
+
+        let bytes = response
+            .bytes()
+            .await
+            .context("Unable to convert body to bytes.")?;
+        store.put(beatmap_id, bytes).await?;
+
         break;
     }
 
     Ok(())
 }
 
-pub fn zip_beatmaps(beatmap_ids: &[u64]) -> anyhow::Result<Vec<u8>> {
-    info!("Zipping {} beatmaps.", beatmap_ids.len());
-    let buffer = Vec::new();
-    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(buffer));
-    let options =
-        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
-
-    for beatmap_id in beatmap_ids {
-        info!("Adding `{}` to zip file.", beatmap_id);
-        zip.start_file(beatmap_id.to_string() + ".osz", options)?;
-        let mut file_path = map_directory();
-        file_path.push(beatmap_id.to_string());
-        file_path.set_extension("osz");
+/// Forwards every chunk the `ZipWriter` flushes to it straight into the
+/// response channel, so the archive is streamed out instead of buffered.
+struct ChannelWriter {
+    sender: mpsc::Sender<anyhow::Result<Bytes>>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.sender
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::BrokenPipe, "zip response was dropped")
+            })?;
+        Ok(buf.len())
+    }
 
-        let beatmap_file = std::fs::read(file_path)?;
-        zip.write_all(&beatmap_file)?;
-        info!("Added `{}` to zip file.", beatmap_id);
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
     }
+}
+
+/// Streams a zip archive of `beatmap_ids` as it's built, instead of
+/// materializing the whole archive in memory before sending it.
+///
+/// A worker task owns the `ZipWriter` and, for each beatmap, copies its
+/// cached bytes through in [`STREAM_CHUNK_SIZE`] chunks so only one file's
+/// buffer is ever live, writing flushed zip bytes into `tx` as it goes. A
+/// beatmap that `store` fails to open is re-downloaded from the osu mirrors
+/// once before giving up. The cached bytes are not validated, so a corrupt
+/// object is streamed as is, and a read error partway through an entry ends
+/// the stream with that error.
+pub fn stream_zip_beatmaps(
+    beatmap_ids: Vec<u64>,
+    store: Arc<dyn BeatmapStore>,
+    mirror_config: Arc<MirrorConfig>,
+    mirror_health: Arc<MirrorHealthTracker>,
+    compression: ZipCompression,
+) -> impl Stream<Item = anyhow::Result<Bytes>> {
+    info!("Zipping {} beatmaps.", beatmap_ids.len());
+    let (tx, rx) = mpsc::channel::<anyhow::Result<Bytes>>(4);
+
+    tokio::task::spawn_blocking(move || {
+        let runtime = tokio::runtime::Handle::current();
+        let options = compression.into_options();
+        let mut zip = zip::ZipWriter::new(ChannelWriter { sender: tx.clone() });
+
+        macro_rules! try_or_send {
+            ($result:expr) => {
+                match $result {
+                    Ok(value) => value,
+                    Err(err) => {
+                        let _ = tx.blocking_send(Err(err.into()));
+                        return;
+                    }
+                }
+            };
+        }
+
+        for beatmap_id in &beatmap_ids {
+            info!("Adding `{}` to zip file.", beatmap_id);
+            try_or_send!(zip.start_file(beatmap_id.to_string() + ".osz", options));
+
+            let mut reader = match runtime.block_on(store.get(*beatmap_id)) {
+                Ok(reader) => reader,
+                Err(err) => {
+                    info!(
+                        "Map `{}` missing or corrupt in the store ({}). Re-downloading from mirrors.",
+                        beatmap_id, err
+                    );
+                    try_or_send!(runtime.block_on(download_map(
+                        *beatmap_id,
+                        store.as_ref(),
+                        mirror_config.as_ref(),
+                        mirror_health.as_ref(),
+                    )));
+                    try_or_send!(runtime.block_on(store.get(*beatmap_id)))
+                }
+            };
+
+            let mut chunk = [0u8; STREAM_CHUNK_SIZE];
+            loop {
+                let read = try_or_send!(runtime
+                    .block_on(tokio::io::AsyncReadExt::read(&mut reader, &mut chunk)));
+                if read == 0 {
+                    break;
+                }
+                try_or_send!(zip.write_all(&chunk[..read]));
+            }
+            info!("Added `{}` to zip file.", beatmap_id);
+        }
+
+        try_or_send!(zip.finish());
+    });
 
-    Ok(zip.finish()?.into_inner())
+    ReceiverStream::new(rx)
 }
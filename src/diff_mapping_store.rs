@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use bonsaidb::{core::keyvalue::AsyncKeyValue, local::AsyncDatabase};
+use sqlx::Row;
+use tracing::debug;
+
+/// Abstracts over where the difficulty-id -> beatmap-id mapping lives, so it
+/// can be backed by an embedded database or shared across instances via SQL.
+#[async_trait]
+pub trait DiffMappingStore: Send + Sync {
+    /// Looks up as many of `diff_ids` as are known, keyed by diff id.
+    /// Missing ids are simply absent from the result.
+    async fn get_many(&self, diff_ids: &[u64]) -> anyhow::Result<HashMap<u64, u64>>;
+
+    /// Records the beatmap id resolved for `diff_id`.
+    async fn put(&self, diff_id: u64, beatmap_id: u64) -> anyhow::Result<()>;
+}
+
+/// Stores the mapping in the embedded `bonsaidb` key-value store.
+pub struct BonsaiDiffMappingStore {
+    db: AsyncDatabase,
+}
+
+impl BonsaiDiffMappingStore {
+    pub fn new(db: AsyncDatabase) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl DiffMappingStore for BonsaiDiffMappingStore {
+    async fn get_many(&self, diff_ids: &[u64]) -> anyhow::Result<HashMap<u64, u64>> {
+        let mut mapping = HashMap::new();
+
+        for diff_id in diff_ids {
+            let beatmap_id = self
+                .db
+                .get_key(diff_id.to_string())
+                .into::<u64>()
+                .await
+                .context("Database can't hold non-u64 values")?;
+
+            match beatmap_id {
+                Some(beatmap_id) => {
+                    mapping.insert(*diff_id, beatmap_id);
+                }
+                None => debug!(diff_id, "Couldn't find entry for difficulty id."),
+            }
+        }
+
+        Ok(mapping)
+    }
+
+    async fn put(&self, diff_id: u64, beatmap_id: u64) -> anyhow::Result<()> {
+        self.db
+            .set_key(diff_id.to_string(), &beatmap_id)
+            .await
+            .context("Error occured writing beatmap_id to database")?;
+        Ok(())
+    }
+}
+
+/// Stores the mapping in a Postgres `difficulty_beatmap(diff_id, beatmap_id)`
+/// table, for deployments that want the mapping shared across instances.
+pub struct PostgresDiffMappingStore {
+    pool: sqlx::PgPool,
+}
+
+impl PostgresDiffMappingStore {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl DiffMappingStore for PostgresDiffMappingStore {
+    async fn get_many(&self, diff_ids: &[u64]) -> anyhow::Result<HashMap<u64, u64>> {
+        let diff_ids: Vec<i64> = diff_ids.iter().map(|diff_id| *diff_id as i64).collect();
+
+        // Runtime-checked rather than `query!`, since this tree ships without a
+        // committed `.sqlx` offline cache: `query!` would otherwise require a
+        // live, already-migrated database at compile time.
+        let rows = sqlx::query("SELECT diff_id, beatmap_id FROM difficulty_beatmap WHERE diff_id = ANY($1)")
+            .bind(&diff_ids)
+            .fetch_all(&self.pool)
+            .await
+            .context("Error occured querying difficulty_beatmap table")?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let diff_id: i64 = row.get("diff_id");
+                let beatmap_id: i64 = row.get("beatmap_id");
+                (diff_id as u64, beatmap_id as u64)
+            })
+            .collect())
+    }
+
+    async fn put(&self, diff_id: u64, beatmap_id: u64) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO difficulty_beatmap (diff_id, beatmap_id) VALUES ($1, $2) \
+             ON CONFLICT (diff_id) DO UPDATE SET beatmap_id = EXCLUDED.beatmap_id",
+        )
+        .bind(diff_id as i64)
+        .bind(beatmap_id as i64)
+        .execute(&self.pool)
+        .await
+        .context("Error occured upserting difficulty_beatmap row")?;
+
+        Ok(())
+    }
+}
@@ -1,20 +1,32 @@
-use anyhow::Context;
-use axum::response::{IntoResponse, Response};
-use bonsaidb::local::AsyncDatabase;
+use std::sync::Arc;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
 use once_cell::sync::Lazy;
-use strum::{EnumIter, IntoEnumIterator};
 
 use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
 
+use beatmap_store::BeatmapStore;
+use diff_mapping_store::DiffMappingStore;
+use mirrors::{MirrorConfig, MirrorHealthTracker};
+
+pub mod auth;
 pub mod beatmap_store;
+pub mod diff_mapping_store;
+pub mod mirrors;
 pub mod osu_api;
 pub mod routes;
 
 #[derive(Clone)]
 pub struct AppState {
     pub osu_access_token: String,
-    pub db: AsyncDatabase,
+    pub diff_mapping_store: Arc<dyn DiffMappingStore>,
+    pub beatmap_store: Arc<dyn BeatmapStore>,
+    pub mirror_config: Arc<MirrorConfig>,
+    pub mirror_health: Arc<MirrorHealthTracker>,
 }
 
 pub static HTTP_CLIENT: Lazy<ClientWithMiddleware> = Lazy::new(|| {
@@ -27,60 +39,30 @@ pub static HTTP_CLIENT: Lazy<ClientWithMiddleware> = Lazy::new(|| {
         .build()
 });
 
-pub struct BeatmapUrlProvider {
-    pub beatmap_id: u64,
-    endpoint: BeatmapEndpointIter,
+// Make our own error that wraps `anyhow::Error`, along with the status code it
+// should be reported as.
+pub struct AppError {
+    status: StatusCode,
+    error: anyhow::Error,
 }
 
-impl BeatmapUrlProvider {
-    pub fn new(beatmap_id: u64) -> Self {
+impl AppError {
+    /// Builds an `AppError` that responds with `status` instead of the
+    /// default `500`, e.g. for turning an `AuthError` into a `401`.
+    pub fn with_status(status: StatusCode, error: impl Into<anyhow::Error>) -> Self {
         Self {
-            beatmap_id,
-            endpoint: BeatmapEndpoint::iter(),
+            status,
+            error: error.into(),
         }
     }
-
-    pub fn get_next_url(&mut self) -> anyhow::Result<String> {
-        Ok(self
-            .endpoint
-            .next()
-            .context("Out of backup endpoints")?
-            .get_download_url(self.beatmap_id))
-    }
 }
 
-/// Beatmap endpoint list.
-/// Since this implements Iterator, it is also the priority queue for the mirror list.
-#[derive(EnumIter)]
-enum BeatmapEndpoint {
-    Catboy,
-    Chimu,
-    Nerinyan,
-}
-
-impl BeatmapEndpoint {
-    pub fn get_download_url(&self, beatmap_id: u64) -> String {
-        match self {
-            BeatmapEndpoint::Chimu => "https://chimu.moe/d/".to_string() + &beatmap_id.to_string(),
-            BeatmapEndpoint::Catboy => {
-                "https://catboy.best/d/".to_string() + &beatmap_id.to_string()
-            }
-            BeatmapEndpoint::Nerinyan => {
-                "https://proxy.nerinyan.moe/d/".to_string() + &beatmap_id.to_string()
-            }
-        }
-    }
-}
-
-// Make our own error that wraps `anyhow::Error`.
-pub struct AppError(anyhow::Error);
-
 // Tell axum how to convert `AppError` into a response.
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
+            self.status,
+            format!("Something went wrong: {}", self.error),
         )
             .into_response()
     }
@@ -93,6 +75,9 @@ where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self(err.into())
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            error: err.into(),
+        }
     }
 }
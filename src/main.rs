@@ -1,33 +1,48 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
+use anyhow::Context;
 use bonsaidb::local::{
     config::{Builder, StorageConfiguration},
     AsyncDatabase,
 };
 use tokio::sync::RwLock;
 
-use axum::{routing::get, Extension};
+use axum::{middleware, routing::get, Extension};
 use osu_zipifier::{
-    beatmap_store::MAP_DIRECTORY, osu_api::refresh_token_periodically, routes::serve_maps, AppState,
+    auth::{require_auth, ApiAuth, ApiKeyAuth, BasicAuth},
+    beatmap_store::{BeatmapStore, LocalFsBeatmapStore, RemoteBeatmapStore, MAP_DIRECTORY},
+    diff_mapping_store::{BonsaiDiffMappingStore, DiffMappingStore, PostgresDiffMappingStore},
+    mirrors::{MirrorConfig, MirrorHealthTracker},
+    osu_api::refresh_token_periodically,
+    routes::serve_maps,
+    AppState,
 };
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     setup_logging();
-    if std::fs::read_dir(MAP_DIRECTORY).is_err() {
-        std::fs::create_dir(MAP_DIRECTORY).unwrap()
-    }
     dotenvy::dotenv().unwrap();
-    let db = AsyncDatabase::open::<()>(StorageConfiguration::new("diff-beatmap.bonsaidb")).await?;
+
+    let beatmap_store = build_beatmap_store()?;
+    let api_auth = build_api_auth();
+    let mirror_config = Arc::new(build_mirror_config()?);
+    let mirror_health = Arc::new(build_mirror_health_tracker());
+    let diff_mapping_store = build_diff_mapping_store().await?;
+
     let shared_state = Arc::new(RwLock::new(AppState {
         osu_access_token: String::new(),
-        db,
+        diff_mapping_store,
+        beatmap_store,
+        mirror_config,
+        mirror_health,
     }));
 
     tokio::task::spawn(refresh_token_periodically(shared_state.clone()));
 
     let app = axum::Router::new()
         .route("/", get(serve_maps))
+        .layer(middleware::from_fn(require_auth))
+        .layer(Extension(api_auth))
         .layer(Extension(shared_state));
 
     axum::Server::bind(&"0.0.0.0:3000".parse().unwrap())
@@ -41,3 +56,88 @@ async fn main() -> anyhow::Result<()> {
 fn setup_logging() {
     tracing_subscriber::fmt::fmt().init();
 }
+
+/// Builds the beatmap cache backend from the environment: a remote object
+/// store when `BEATMAP_STORE_BUCKET` is set, otherwise the local filesystem.
+fn build_beatmap_store() -> anyhow::Result<Arc<dyn BeatmapStore>> {
+    match std::env::var("BEATMAP_STORE_BUCKET") {
+        Ok(bucket) => {
+            let endpoint = std::env::var("BEATMAP_STORE_ENDPOINT")
+                .expect("BEATMAP_STORE_ENDPOINT env var is not set!");
+            let region = std::env::var("BEATMAP_STORE_REGION")
+                .expect("BEATMAP_STORE_REGION env var is not set!");
+            let access_key = std::env::var("BEATMAP_STORE_ACCESS_KEY")
+                .expect("BEATMAP_STORE_ACCESS_KEY env var is not set!");
+            let secret_key = std::env::var("BEATMAP_STORE_SECRET_KEY")
+                .expect("BEATMAP_STORE_SECRET_KEY env var is not set!");
+            Ok(Arc::new(RemoteBeatmapStore::new(
+                endpoint, bucket, region, access_key, secret_key,
+            )?))
+        }
+        Err(_) => {
+            if std::fs::read_dir(MAP_DIRECTORY).is_err() {
+                std::fs::create_dir(MAP_DIRECTORY).unwrap()
+            }
+            Ok(Arc::new(LocalFsBeatmapStore::new()))
+        }
+    }
+}
+
+/// Builds the API auth backend from the environment: a static API key list
+/// when `API_KEYS` is set, otherwise HTTP Basic against a single configured
+/// username/password pair.
+fn build_api_auth() -> Arc<dyn ApiAuth> {
+    match std::env::var("API_KEYS") {
+        Ok(api_keys) => {
+            let keys = api_keys.split(',').map(str::to_string).collect();
+            Arc::new(ApiKeyAuth::new(keys))
+        }
+        Err(_) => {
+            let username =
+                std::env::var("BASIC_AUTH_USERNAME").expect("BASIC_AUTH_USERNAME env var is not set!");
+            let password =
+                std::env::var("BASIC_AUTH_PASSWORD").expect("BASIC_AUTH_PASSWORD env var is not set!");
+            Arc::new(BasicAuth::new(username, password))
+        }
+    }
+}
+
+/// Loads the mirror list config, from the path in `MIRRORS_CONFIG_PATH` or
+/// `mirrors.toml` by default.
+fn build_mirror_config() -> anyhow::Result<MirrorConfig> {
+    let path = std::env::var("MIRRORS_CONFIG_PATH").unwrap_or_else(|_| "mirrors.toml".to_string());
+    MirrorConfig::load(std::path::Path::new(&path))
+}
+
+/// Builds the mirror health tracker, with the cool-down from
+/// `MIRROR_COOLDOWN_SECS` or 5 minutes by default.
+fn build_mirror_health_tracker() -> MirrorHealthTracker {
+    let cooldown_secs = std::env::var("MIRROR_COOLDOWN_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(5 * 60);
+    MirrorHealthTracker::new(Duration::from_secs(cooldown_secs))
+}
+
+/// Builds the diff->beatmap mapping backend from the environment: Postgres
+/// when `DIFF_MAPPING_DATABASE_URL` is set, otherwise the embedded bonsaidb
+/// key-value store.
+async fn build_diff_mapping_store() -> anyhow::Result<Arc<dyn DiffMappingStore>> {
+    match std::env::var("DIFF_MAPPING_DATABASE_URL") {
+        Ok(database_url) => {
+            let pool = sqlx::PgPool::connect(&database_url).await?;
+            sqlx::migrate!("./migrations")
+                .run(&pool)
+                .await
+                .context("Unable to run difficulty_beatmap migrations")?;
+            Ok(Arc::new(PostgresDiffMappingStore::new(pool)))
+        }
+        Err(_) => {
+            let db = AsyncDatabase::open::<()>(StorageConfiguration::new(
+                "diff-beatmap.bonsaidb",
+            ))
+            .await?;
+            Ok(Arc::new(BonsaiDiffMappingStore::new(db)))
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+/// One entry in the mirror list config file: a named endpoint, a URL
+/// template containing a literal `{id}` placeholder, its priority relative
+/// to the other mirrors (higher goes first), and a weight used to order
+/// mirrors that share the same priority (higher goes first).
+#[derive(Debug, Clone, Deserialize)]
+pub struct MirrorEntry {
+    pub name: String,
+    pub url_template: String,
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl MirrorEntry {
+    pub fn download_url(&self, beatmap_id: u64) -> String {
+        self.url_template.replace("{id}", &beatmap_id.to_string())
+    }
+}
+
+/// The ordered list of osu beatmap mirrors to fail over across, loaded from
+/// a TOML config file so operators can add or reorder mirrors without
+/// recompiling.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MirrorConfig {
+    pub mirrors: Vec<MirrorEntry>,
+}
+
+impl MirrorConfig {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read mirror config at `{}`.", path.display()))?;
+        toml::from_str(&contents).context("Unable to parse mirror config.")
+    }
+
+    /// Mirrors in priority order, highest priority first; mirrors tied on
+    /// priority are ordered by descending weight, so a heavier mirror is
+    /// preferred over a lighter one at the same priority tier.
+    fn by_priority(&self) -> Vec<MirrorEntry> {
+        let mut mirrors = self.mirrors.clone();
+        mirrors.sort_by(|a, b| b.priority.cmp(&a.priority).then(b.weight.cmp(&a.weight)));
+        mirrors
+    }
+}
+
+/// Tracks which mirrors have recently failed, so [`BeatmapUrlProvider`] can
+/// skip them until their cool-down elapses instead of hammering a mirror
+/// that's down.
+pub struct MirrorHealthTracker {
+    cooldown_until: RwLock<HashMap<String, Instant>>,
+    cooldown_duration: Duration,
+}
+
+impl MirrorHealthTracker {
+    pub fn new(cooldown_duration: Duration) -> Self {
+        Self {
+            cooldown_until: RwLock::new(HashMap::new()),
+            cooldown_duration,
+        }
+    }
+
+    /// Marks `mirror_name` as failing, putting it in cool-down.
+    pub fn record_failure(&self, mirror_name: &str) {
+        self.cooldown_until
+            .write()
+            .unwrap()
+            .insert(mirror_name.to_string(), Instant::now() + self.cooldown_duration);
+    }
+
+    /// Whether `mirror_name` is currently healthy, i.e. not in cool-down.
+    pub fn is_healthy(&self, mirror_name: &str) -> bool {
+        match self.cooldown_until.read().unwrap().get(mirror_name) {
+            Some(cooldown_until) => Instant::now() >= *cooldown_until,
+            None => true,
+        }
+    }
+}
+
+impl Default for MirrorHealthTracker {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(5 * 60))
+    }
+}
+
+pub struct BeatmapUrlProvider<'a> {
+    pub beatmap_id: u64,
+    candidates: std::vec::IntoIter<MirrorEntry>,
+    health: &'a MirrorHealthTracker,
+    current_mirror: Option<MirrorEntry>,
+}
+
+impl<'a> BeatmapUrlProvider<'a> {
+    pub fn new(beatmap_id: u64, config: &MirrorConfig, health: &'a MirrorHealthTracker) -> Self {
+        Self {
+            beatmap_id,
+            candidates: config.by_priority().into_iter(),
+            health,
+            current_mirror: None,
+        }
+    }
+
+    /// Returns the next healthy mirror's download URL, skipping any mirror
+    /// currently in cool-down and re-admitting it once the cool-down elapses.
+    pub fn get_next_url(&mut self) -> anyhow::Result<String> {
+        let mirror = self
+            .candidates
+            .by_ref()
+            .find(|mirror| self.health.is_healthy(&mirror.name))
+            .context("Out of backup endpoints")?;
+
+        let url = mirror.download_url(self.beatmap_id);
+        self.current_mirror = Some(mirror);
+        Ok(url)
+    }
+
+    /// The mirror the last URL returned by [`Self::get_next_url`] came from.
+    pub fn current_mirror_name(&self) -> Option<&str> {
+        self.current_mirror.as_ref().map(|mirror| mirror.name.as_str())
+    }
+}
@@ -1,11 +1,10 @@
 use std::{sync::Arc, time::Duration};
 
 use anyhow::{bail, Context};
-use bonsaidb::core::keyvalue::AsyncKeyValue;
 use futures::future::try_join_all;
 use reqwest::StatusCode;
 use tokio::sync::RwLock;
-use tracing::{debug, error, info, instrument};
+use tracing::{error, info, instrument};
 
 use crate::{AppState, HTTP_CLIENT};
 
@@ -70,46 +69,46 @@ pub async fn get_beatmap_id_from_diff_ids(
     }
 
     let (mut beatmap_ids, difficulty_ids) =
-        get_beatmap_ids_from_db(difficulty_ids, state.clone()).await;
+        get_beatmap_ids_from_db(difficulty_ids, state.clone()).await?;
+
+    let diff_mapping_store = state.read().await.diff_mapping_store.clone();
 
     let beatmap_id_futures: Vec<_> = difficulty_ids
         .iter()
-        .map(|diff_id| async {
-            info!(diff_id = *diff_id, "Fetching beatmap id from osu API");
-            let response = HTTP_CLIENT
-                .get(fetch_beatmap_id_url(*diff_id))
-                .bearer_auth(state.read().await.osu_access_token.clone())
-                .send()
-                .await?;
-
-            if response.status() != StatusCode::OK {
-                bail!("Failed to fetch beatmap ID from difficulty ID.")
+        .map(|diff_id| {
+            let diff_mapping_store = diff_mapping_store.clone();
+            let state = state.clone();
+            async move {
+                info!(diff_id = *diff_id, "Fetching beatmap id from osu API");
+                let response = HTTP_CLIENT
+                    .get(fetch_beatmap_id_url(*diff_id))
+                    .bearer_auth(state.read().await.osu_access_token.clone())
+                    .send()
+                    .await?;
+
+                if response.status() != StatusCode::OK {
+                    bail!("Failed to fetch beatmap ID from difficulty ID.")
+                }
+
+                let beatmap_id = response
+                    .json::<serde_json::Value>()
+                    .await?
+                    .as_object()
+                    .expect("Converting successful beatmap response should not fail.")
+                    .get("beatmapset_id")
+                    .expect("Successful beatmap request should have \"beatmapset_id\" field.")
+                    .as_u64()
+                    .expect("\"beatmapset_id\" must be a Number");
+
+                info!(
+                    beatmap_id,
+                    diff_id = *diff_id,
+                    "Saving beatmap ID for the diff to the database."
+                );
+                diff_mapping_store.put(*diff_id, beatmap_id).await?;
+
+                Ok(beatmap_id)
             }
-
-            let beatmap_id = response
-                .json::<serde_json::Value>()
-                .await?
-                .as_object()
-                .expect("Converting successful beatmap response should not fail.")
-                .get("beatmapset_id")
-                .expect("Successful beatmap request should have \"beatmapset_id\" field.")
-                .as_u64()
-                .expect("\"beatmapset_id\" must be a Number");
-
-            info!(
-                beatmap_id,
-                diff_id = *diff_id,
-                "Saving beatmap ID for the diff to the database."
-            );
-            state
-                .read()
-                .await
-                .db
-                .set_key(diff_id.to_string(), &beatmap_id)
-                .await
-                .context("Error occured writing beatmap_id to database")?;
-
-            Ok(beatmap_id)
         })
         .collect();
 
@@ -121,9 +120,7 @@ pub async fn get_beatmap_id_from_diff_ids(
 async fn get_beatmap_ids_from_db(
     difficulty_ids: &[u64],
     state: Arc<RwLock<AppState>>,
-) -> (Vec<u64>, Vec<u64>) {
-    let mut beatmap_ids = Vec::new();
-    let mut unknown_difficulty_ids = Vec::new();
+) -> anyhow::Result<(Vec<u64>, Vec<u64>)> {
     let mut difficulty_ids = Vec::from(difficulty_ids);
     difficulty_ids.sort();
     difficulty_ids.dedup();
@@ -133,24 +130,19 @@ async fn get_beatmap_ids_from_db(
         "Trying to resolve difficulty ids. {:?}", difficulty_ids
     );
 
+    let diff_mapping_store = state.read().await.diff_mapping_store.clone();
+    let known_mapping = diff_mapping_store.get_many(&difficulty_ids).await?;
+
+    let mut beatmap_ids = Vec::new();
+    let mut unknown_difficulty_ids = Vec::new();
+
     for diff_id in &difficulty_ids {
-        match state
-            .read()
-            .await
-            .db
-            .get_key(diff_id.to_string())
-            .into::<u64>()
-            .await
-            .expect("Database can't hold non-u64 values")
-        {
+        match known_mapping.get(diff_id) {
             Some(beatmap_id) => {
                 info!(diff_id, beatmap_id, "Found entry for difficulty id.",);
-                beatmap_ids.push(beatmap_id)
-            }
-            None => {
-                debug!(diff_id, "Couldn't find entry for difficulty id.");
-                unknown_difficulty_ids.push(*diff_id)
+                beatmap_ids.push(*beatmap_id)
             }
+            None => unknown_difficulty_ids.push(*diff_id),
         }
     }
 
@@ -159,7 +151,7 @@ async fn get_beatmap_ids_from_db(
         beatmap_ids.len(),
         difficulty_ids.len()
     );
-    (beatmap_ids, unknown_difficulty_ids)
+    Ok((beatmap_ids, unknown_difficulty_ids))
 }
 
 pub async fn refresh_token_periodically(state: Arc<RwLock<AppState>>) -> anyhow::Result<()> {
@@ -1,4 +1,5 @@
 use axum::{
+    body::StreamBody,
     extract::Json,
     http::{header, HeaderMap},
     response::IntoResponse,
@@ -10,11 +11,24 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 
 use crate::{
-    beatmap_store::{download_map, find_non_downloaded_maps, zip_beatmaps},
+    beatmap_store::{download_map, stream_zip_beatmaps, ZipCompression},
     osu_api::get_beatmap_id_from_diff_ids,
     AppError, AppState,
 };
 
+/// Compression used for the streamed response zip. `Stored` keeps the
+/// current "no compression" behaviour; set `ZIP_DEFLATE_LEVEL` to opt into
+/// `Deflated` at that level instead.
+fn response_compression() -> ZipCompression {
+    match std::env::var("ZIP_DEFLATE_LEVEL")
+        .ok()
+        .and_then(|level| level.parse::<i32>().ok())
+    {
+        Some(level) => ZipCompression::Deflated { level },
+        None => ZipCompression::Stored,
+    }
+}
+
 #[derive(Deserialize)]
 pub struct ServeMapsRequest {
     maps: Vec<u64>,
@@ -43,6 +57,15 @@ pub async fn serve_maps(
     Json(request): Json<ServeMapsRequest>,
     Extension(state): Extension<Arc<RwLock<AppState>>>,
 ) -> Result<impl IntoResponse, AppError> {
+    let (beatmap_store, mirror_config, mirror_health) = {
+        let state = state.read().await;
+        (
+            state.beatmap_store.clone(),
+            state.mirror_config.clone(),
+            state.mirror_health.clone(),
+        )
+    };
+
     let mut map_list = match request.id_type {
         IdType::Beatmap => request.maps,
         IdType::Difficulty => get_beatmap_id_from_diff_ids(&request.maps, state).await?,
@@ -50,29 +73,40 @@ pub async fn serve_maps(
     map_list.sort_unstable();
     map_list.dedup();
 
-    let absent_maps = find_non_downloaded_maps(&map_list)?;
+    let absent_maps = beatmap_store.find_non_downloaded(&map_list).await?;
 
     if !absent_maps.is_empty() {
         let mut download_futures = Vec::new();
 
         for map_id in absent_maps {
-            download_futures.push(download_map(map_id));
+            download_futures.push(download_map(
+                map_id,
+                beatmap_store.as_ref(),
+                mirror_config.as_ref(),
+                mirror_health.as_ref(),
+            ));
         }
 
         try_join_all(download_futures).await?;
     }
 
-    let zipped_maps = tokio::task::spawn_blocking(move || zip_beatmaps(&map_list)).await??;
+    let zip_stream = stream_zip_beatmaps(
+        map_list,
+        beatmap_store,
+        mirror_config,
+        mirror_health,
+        response_compression(),
+    );
 
-    Ok(return_zip_file_with_headers(zipped_maps))
+    Ok(return_zip_file_with_headers(StreamBody::new(zip_stream)))
 }
 
-fn return_zip_file_with_headers(data: Vec<u8>) -> impl IntoResponse {
+fn return_zip_file_with_headers(body: impl IntoResponse) -> impl IntoResponse {
     let mut headers = HeaderMap::new();
     headers.insert(header::CONTENT_TYPE, "application/zip".parse().unwrap());
     headers.insert(
         header::CONTENT_DISPOSITION,
         "attachment; filename=\"maps.zip\"".parse().unwrap(),
     );
-    (headers, data)
+    (headers, body)
 }